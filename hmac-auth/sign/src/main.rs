@@ -1,8 +1,17 @@
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-/// Emit the SHA-256 HMAC signature for a given string, provided as the first
-/// and only command line argument.
+/// Default validity window for a signed URL, in seconds, when no duration is
+/// given on the command line.
+const DEFAULT_VALIDITY_SECS: u64 = 300;
+
+/// Emit an AWS-presigned-URL-style SHA-256 HMAC signature for a given path,
+/// the first and required command line argument. An optional second
+/// argument gives the signature's validity duration in seconds (defaults to
+/// `DEFAULT_VALIDITY_SECS`). Prints the `expires` unix timestamp and the
+/// hex-encoded signature, both of which must be attached to the request as
+/// `expires` and `signature` query parameters.
 ///
 /// Uses the SECRET_KEY environment variable as the key to initialize the
 /// HMAC. SECRET_KEY must be a hexidecimal value with an even number of
@@ -13,13 +22,27 @@ fn main() {
     let secret_key = hex::decode(secret_key).expect("secret key should be hex");
 
     let args = std::env::args().collect::<Vec<String>>();
-    if args.len() != 2 {
-        panic!("exactly 1 arg allowed");
+    if args.len() < 2 || args.len() > 3 {
+        panic!("usage: sign <path> [validity_secs]");
     }
+    let path = &args[1];
+    let validity_secs: u64 = args
+        .get(2)
+        .map(|s| s.parse().expect("validity_secs should be an integer"))
+        .unwrap_or(DEFAULT_VALIDITY_SECS);
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("current time should be after unix epoch")
+        .as_secs();
+    let expires = now + validity_secs;
+
+    let canonical = format!("{path}?expires={expires}");
 
     let mut mac = Hmac::<Sha256>::new_from_slice(&secret_key).unwrap();
-    mac.update(args[1].as_bytes());
+    mac.update(canonical.as_bytes());
     let signature = mac.finalize().into_bytes();
 
-    println!("{}", hex::encode(signature));
+    println!("expires={expires}");
+    println!("signature={}", hex::encode(signature));
 }
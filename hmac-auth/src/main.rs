@@ -1,8 +1,9 @@
 use anyhow::{Context, Result, anyhow};
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
 use wstd::http::{
-    IntoBody, Method, Request, Response, StatusCode,
+    IntoBody, Method, Request, Response, StatusCode, Uri,
     body::IncomingBody,
     server::{Finished, Responder},
 };
@@ -29,12 +30,25 @@ fn handle(req: Request<IncomingBody>) -> Result<String> {
         Err(anyhow!("unsupported method {}", req.method()).context(StatusCode::METHOD_NOT_ALLOWED))?
     }
 
+    // Reject expired or malformed `expires` before doing any HMAC work, so a
+    // captured signed URL stops working once its validity window has passed.
+    let expires = request_expiry(&req)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("reading current time")?
+        .as_secs();
+    if now >= expires {
+        Err(anyhow!("signature expired at {expires}, now is {now}").context(StatusCode::FORBIDDEN))?
+    }
+
     let secret_key = secret_key().context("calucating secret key")?;
 
-    // Calculate HMAC of the request URI
+    // Calculate HMAC of the canonical request string: the path plus its query
+    // parameters, sorted and with `signature` itself stripped out, so that a
+    // signed URL verifies the same regardless of how the query parameters
+    // were ordered.
     let mut mac = Hmac::<Sha256>::new_from_slice(&secret_key).context("constucting hmac")?;
-    let uri = req.uri().to_string();
-    mac.update(uri.as_bytes());
+    mac.update(canonical_string(req.uri()).as_bytes());
 
     // Verify HMAC matches signature. verify_slice performs a constant-time
     // comparison.
@@ -45,15 +59,61 @@ fn handle(req: Request<IncomingBody>) -> Result<String> {
     Ok("authorized".to_string())
 }
 
-/// Extract the request's Signature header, which should contain a hexadecimal
-/// value.
+/// Extract the request's `signature` query parameter, which should contain a
+/// hexadecimal value.
 fn request_signature(req: &Request<IncomingBody>) -> Result<Vec<u8>> {
-    let headers = req.headers();
-    let signature = headers.get("signature");
-    if signature.is_none() {
-        Err(anyhow!("missing Signature header").context(StatusCode::BAD_REQUEST))?
+    let signature = query_param(req.uri(), "signature")
+        .ok_or_else(|| anyhow!("missing signature query parameter").context(StatusCode::BAD_REQUEST))?;
+    hex::decode(signature).context(StatusCode::BAD_REQUEST)
+}
+
+/// Extract the request's `expires` query parameter, a unix epoch seconds
+/// timestamp after which the signature is no longer valid.
+fn request_expiry(req: &Request<IncomingBody>) -> Result<u64> {
+    let expires = query_param(req.uri(), "expires")
+        .ok_or_else(|| anyhow!("missing expires query parameter").context(StatusCode::BAD_REQUEST))?;
+    expires
+        .parse()
+        .map_err(|_| anyhow!("invalid expires query parameter").context(StatusCode::BAD_REQUEST))
+}
+
+/// Look up a single query parameter by key. Doesn't percent-decode, since
+/// none of the parameters we deal with need it.
+fn query_param<'a>(uri: &'a Uri, key: &str) -> Option<&'a str> {
+    uri.query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
+/// Build the string that gets HMAC'd to produce (or verify) a signature: the
+/// request path followed by its query parameters, sorted by key with the
+/// `signature` parameter itself removed. Sorting makes verification robust
+/// to a client or proxy reordering query parameters; stripping `signature`
+/// is necessary because the signature obviously wasn't part of what got
+/// signed.
+fn canonical_string(uri: &Uri) -> String {
+    let mut pairs: Vec<(&str, &str)> = uri
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| pair.split_once('='))
+        .filter(|(k, _)| *k != "signature")
+        .collect();
+    pairs.sort();
+
+    if pairs.is_empty() {
+        uri.path().to_string()
+    } else {
+        let query = pairs
+            .into_iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join("&");
+        format!("{}?{query}", uri.path())
     }
-    hex::decode(signature.expect("validated signature is some")).context(StatusCode::BAD_REQUEST)
 }
 
 /// The wstd http server runs `handle` and then packages the success or error into
@@ -1,12 +1,13 @@
 use anyhow::{anyhow, Context, Result};
 use jaq_core::Filter;
 use jaq_json::Val;
+use std::collections::{HashMap, VecDeque};
 use std::rc::Rc;
-use std::sync::OnceLock;
+use std::sync::{Arc, Mutex, OnceLock};
 use wstd::http::{
     body::IncomingBody,
     server::{Finished, Responder},
-    IntoBody, Request, Response,
+    IntoBody, Request, Response, StatusCode,
 };
 
 #[wstd::http_server]
@@ -18,7 +19,12 @@ async fn main(req: Request<IncomingBody>, responder: Responder) -> Finished {
             .body(body.into_body())
             .unwrap(),
         Err(e) => Response::builder()
-            .status(500)
+            .status(
+                // If handle's Error contains a StatusCode in the context, we
+                // use it here, or default to 500 internal server error.
+                e.downcast_ref::<StatusCode>()
+                    .unwrap_or(&StatusCode::INTERNAL_SERVER_ERROR),
+            )
             .body(format!("{e:?}").into_body())
             .unwrap(),
     };
@@ -26,10 +32,13 @@ async fn main(req: Request<IncomingBody>, responder: Responder) -> Finished {
 }
 
 type Filt = Filter<jaq_core::Native<Val>>;
-static FILTER: OnceLock<Filt> = OnceLock::new();
+
+/// Program used when the request doesn't supply its own `filter` query
+/// parameter or `X-Jq-Program` header.
+const DEFAULT_PROGRAM: &str = ".[]";
 
 async fn handle(req: Request<IncomingBody>) -> Result<String> {
-    let filter = FILTER.get().expect("filter should be initialized");
+    let program = jq_program(&req);
     let inputs = jaq_core::RcIter::new(core::iter::empty());
 
     let body = req.into_body().bytes().await?;
@@ -37,6 +46,29 @@ async fn handle(req: Request<IncomingBody>) -> Result<String> {
     let body_val =
         hifijson::token::Lex::exactly_one(&mut lexer, Val::parse).context("parsing body json")?;
 
+    // The cache lock is held for the lookup and, on a miss, for compiling and
+    // inserting the program. The cache stores an `Arc` around each compiled
+    // filter, so cloning it out and dropping the lock before running it is
+    // guaranteed cheap (a refcount bump, not a copy of the filter), regardless
+    // of whether `Filt` itself is `Clone`. This keeps a slow caller-supplied
+    // program from blocking every other request against the cache mutex
+    // while it runs.
+    let filter = {
+        let mut cache = filter_cache().lock().unwrap();
+        if cache.entries.contains_key(&program) {
+            cache.touch(&program);
+        } else {
+            let filter =
+                compile_filter(&program).map_err(|e| anyhow!(e).context(StatusCode::BAD_REQUEST))?;
+            cache.insert(program.clone(), filter);
+        }
+        cache
+            .entries
+            .get(&program)
+            .expect("just inserted or already present")
+            .clone()
+    };
+
     let vals = filter
         .run((jaq_core::Ctx::new([], &inputs), body_val))
         .collect::<Result<Vec<Val>, jaq_json::Error>>()
@@ -45,29 +77,93 @@ async fn handle(req: Request<IncomingBody>) -> Result<String> {
     Ok(format!("{val}"))
 }
 
-#[component_init::init]
-fn init() {
-    let filt = create_filter().expect("creating filter");
-    FILTER
-        .set(filt)
-        .ok()
-        .expect("filter should be uninitialized")
+/// Pull the jq program to run out of the request: the `filter` query
+/// parameter takes precedence, then the `X-Jq-Program` header, falling back
+/// to `DEFAULT_PROGRAM` if neither is present.
+fn jq_program(req: &Request<IncomingBody>) -> String {
+    if let Some(program) = req.uri().query().and_then(|q| query_param(q, "filter")) {
+        return program;
+    }
+    if let Some(header) = req.headers().get("X-Jq-Program").and_then(|v| v.to_str().ok()) {
+        return header.to_string();
+    }
+    DEFAULT_PROGRAM.to_string()
+}
+
+/// Look up a single query parameter by key, without percent-decoding.
+fn query_param(query: &str, key: &str) -> Option<String> {
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))
+        .map(|v| v.to_string())
+}
+
+/// Capacity of the compiled-filter LRU. Past this many distinct programs,
+/// the least-recently-used one is evicted to make room.
+const FILTER_CACHE_CAPACITY: usize = 16;
+
+/// A small least-recently-used cache of compiled jq filters, keyed by the
+/// exact program text, since compiling through `Loader`/`Compiler` is
+/// expensive and callers often repeat the same program.
+struct FilterCache {
+    entries: HashMap<String, Arc<Filt>>,
+    // Front is least-recently-used, back is most-recently-used.
+    recency: VecDeque<String>,
 }
 
-fn create_filter() -> Result<Filt> {
+impl FilterCache {
+    fn new() -> Self {
+        FilterCache {
+            entries: HashMap::new(),
+            recency: VecDeque::new(),
+        }
+    }
+
+    fn touch(&mut self, program: &str) {
+        if let Some(pos) = self.recency.iter().position(|p| p == program) {
+            let key = self.recency.remove(pos).expect("position came from this deque");
+            self.recency.push_back(key);
+        }
+    }
+
+    fn insert(&mut self, program: String, filter: Filt) {
+        if !self.entries.contains_key(&program) && self.entries.len() >= FILTER_CACHE_CAPACITY {
+            if let Some(oldest) = self.recency.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.recency.push_back(program.clone());
+        self.entries.insert(program, Arc::new(filter));
+    }
+}
+
+fn filter_cache() -> &'static Mutex<FilterCache> {
+    static CACHE: OnceLock<Mutex<FilterCache>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(FilterCache::new()))
+}
+
+fn compile_filter(program: &str) -> Result<Filt, String> {
     use jaq_core::load::{Arena, File, Loader};
     let file = File {
-        code: ".[]",
+        code: program,
         path: (),
     };
     let arena = Arena::default();
     let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
     let modules = loader
         .load(&arena, file)
-        .map_err(|es| anyhow!("loader errors {es:?}"))?;
-    let filter = jaq_core::Compiler::default()
+        .map_err(|es| format!("loader errors {es:?}"))?;
+    jaq_core::Compiler::default()
         .with_funs(jaq_std::funs().chain(jaq_json::funs()))
         .compile(modules)
-        .map_err(|es| anyhow!("compiler errors {es:?}"))?;
-    Ok(filter)
+        .map_err(|es| format!("compiler errors {es:?}"))
+}
+
+#[component_init::init]
+fn init() {
+    let filter = compile_filter(DEFAULT_PROGRAM).expect("compiling default filter");
+    filter_cache()
+        .lock()
+        .unwrap()
+        .insert(DEFAULT_PROGRAM.to_string(), filter);
 }
@@ -0,0 +1,97 @@
+//! A tiny TTL memoization cache sitting in front of the open-meteo geocoding
+//! and forecast calls, so a burst of requests for the same location makes at
+//! most one upstream call per unique key within the TTL window.
+
+use crate::{Location, Weather};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// How long a cached entry stays fresh. Overridable at build time via the
+/// CACHE_TTL_SECS environment variable; defaults to 5 minutes.
+fn ttl() -> Duration {
+    const CACHE_TTL_SECS: Option<&str> = option_env!("CACHE_TTL_SECS");
+    let secs = CACHE_TTL_SECS.and_then(|s| s.parse().ok()).unwrap_or(300);
+    Duration::from_secs(secs)
+}
+
+/// Cache key for a geocoding lookup: case-insensitive city name plus result
+/// count.
+type LocationKey = (String, u32);
+
+fn locations_cache() -> &'static Mutex<HashMap<LocationKey, (Instant, Vec<Location>)>> {
+    static LOCATIONS: OnceLock<Mutex<HashMap<LocationKey, (Instant, Vec<Location>)>>> = OnceLock::new();
+    LOCATIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Build the cache key for a geocoding lookup.
+pub fn location_key(city: &str, count: u32) -> LocationKey {
+    (city.to_lowercase(), count)
+}
+
+/// Return a fresh cached geocoding result for `key`, if one exists. Evicts
+/// the entry if it's gone stale.
+pub fn get_locations(key: &LocationKey) -> Option<Vec<Location>> {
+    let mut cache = locations_cache().lock().unwrap();
+    match cache.get(key) {
+        Some((inserted_at, value)) if inserted_at.elapsed() < ttl() => Some(value.clone()),
+        Some(_) => {
+            cache.remove(key);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Cache a geocoding result for `key`, timestamped now.
+pub fn put_locations(key: LocationKey, value: Vec<Location>) {
+    locations_cache()
+        .lock()
+        .unwrap()
+        .insert(key, (Instant::now(), value));
+}
+
+/// Cache key for a forecast lookup: latitude/longitude quantized to 4
+/// decimal places and truncated to integers, so nearby floating point
+/// representations of the same location collapse to one entry, and so the
+/// key can be hashed without the usual float-hashing pitfalls. The selected
+/// metrics are also part of the key, so a `metrics=uv,aqi` lookup doesn't
+/// get handed a cached `temp,rain` result for the same coordinates.
+type WeatherKey = (i32, i32, String);
+
+fn forecasts_cache() -> &'static Mutex<HashMap<WeatherKey, (Instant, Weather)>> {
+    static FORECASTS: OnceLock<Mutex<HashMap<WeatherKey, (Instant, Weather)>>> = OnceLock::new();
+    FORECASTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Build the cache key for a forecast lookup. `metrics` should be a
+/// canonical string identifying which metrics were requested.
+pub fn weather_key(latitude: f64, longitude: f64, metrics: &str) -> WeatherKey {
+    (
+        (latitude * 10_000.0) as i32,
+        (longitude * 10_000.0) as i32,
+        metrics.to_string(),
+    )
+}
+
+/// Return a fresh cached forecast for `key`, if one exists. Evicts the entry
+/// if it's gone stale.
+pub fn get_weather(key: &WeatherKey) -> Option<Weather> {
+    let mut cache = forecasts_cache().lock().unwrap();
+    match cache.get(key) {
+        Some((inserted_at, value)) if inserted_at.elapsed() < ttl() => Some(value.clone()),
+        Some(_) => {
+            cache.remove(key);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Cache a forecast result for `key`, timestamped now.
+pub fn put_weather(key: WeatherKey, value: Weather) {
+    forecasts_cache()
+        .lock()
+        .unwrap()
+        .insert(key, (Instant::now(), value));
+}
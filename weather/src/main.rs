@@ -14,17 +14,26 @@
 //! get the current temperature and rain accumulation in each of those
 //! locations.
 //!
+//! The `metrics` query parameter selects which measurements to report, as a
+//! comma-separated list of `temp`, `rain`, `uv`, and `aqi` (defaults to
+//! `temp,rain`). `uv` comes from the forecast api above; `aqi` (European AQI
+//! and PM2.5) comes from a second, concurrent request to
+//! `air-quality-api.open-meteo.com`.
+//!
 //! The complete set of locations and weather reports are retuned as a json
 //! array of records.
 
 use anyhow::{Context, Result, anyhow};
 use serde::{Deserialize, Serialize};
 use wstd::http::{
-    Client, IntoBody, Method, Request, Response, StatusCode, Uri,
+    IntoBody, Method, Request, Response, StatusCode, Uri,
     body::IncomingBody,
     server::{Finished, Responder},
 };
 
+mod cache;
+mod policy;
+
 /// Be polite: user-agent tells server where these results came from, so they
 /// can easily block abuse
 const USER_AGENT: &str = "Weather wasi-http demo (https://github.com/pchickey/wasi-http-demos)";
@@ -39,6 +48,7 @@ async fn handle(req: Request<IncomingBody>) -> Result<String> {
     }
     // Parse the query out of the request
     let query = get_query(&req).context("getting location name")?;
+    let metrics = Metrics::parse(&query.metrics);
 
     // Search for the locations in the query
     let location_results = fetch_locations(&query)
@@ -58,7 +68,7 @@ async fn handle(req: Request<IncomingBody>) -> Result<String> {
         // For each location found, constuct a future which fetches the
         // weather, then returns the record of location, weather
         .map(|location| async move {
-            let weather = fetch_weather(&location)
+            let weather = fetch_weather(&location, metrics)
                 .await
                 .with_context(|| format!("fetching weather for {}", location.qualified_name))?;
             Ok::<_, anyhow::Error>(Item { location, weather })
@@ -78,17 +88,24 @@ async fn handle(req: Request<IncomingBody>) -> Result<String> {
 }
 
 /// The query string given to this server contains a city, and optionally a
-/// count.
+/// count and a set of weather metrics to report.
 #[derive(Deserialize)]
 struct Query {
     city: String,
     #[serde(default = "default_count")]
     count: u32,
+    #[serde(default = "default_metrics")]
+    metrics: String,
 }
 /// When the count is not given in the query string, it defaults to this number
 const fn default_count() -> u32 {
     10
 }
+/// When `metrics` is not given in the query string, it defaults to this, so
+/// existing callers see no change in the shape of the response.
+fn default_metrics() -> String {
+    "temp,rain".to_string()
+}
 /// Default Query for when none is given. Portland is a good enough location
 /// for me, so its good enough for the demo.
 impl Default for Query {
@@ -96,10 +113,73 @@ impl Default for Query {
         Query {
             city: "Portland".to_string(),
             count: default_count(),
+            metrics: default_metrics(),
         }
     }
 }
 
+/// Which weather metrics a caller wants reported, parsed out of the
+/// `metrics` query parameter. Unrecognized metric names are ignored, same
+/// spirit as the rest of this demo's lenient query parsing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Metrics {
+    temp: bool,
+    rain: bool,
+    uv: bool,
+    aqi: bool,
+}
+
+impl Metrics {
+    fn parse(s: &str) -> Self {
+        let mut metrics = Metrics {
+            temp: false,
+            rain: false,
+            uv: false,
+            aqi: false,
+        };
+        for metric in s.split(',') {
+            match metric.trim() {
+                "temp" => metrics.temp = true,
+                "rain" => metrics.rain = true,
+                "uv" => metrics.uv = true,
+                "aqi" => metrics.aqi = true,
+                _ => (),
+            }
+        }
+        metrics
+    }
+
+    /// The forecast api's `current` query parameter value for the metrics
+    /// it covers (temperature, rain, UV index).
+    fn forecast_current(&self) -> String {
+        [
+            self.temp.then_some("temperature_2m"),
+            self.rain.then_some("rain"),
+            self.uv.then_some("uv_index"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(",")
+    }
+
+    /// A canonical string identifying which metrics are selected, used as
+    /// part of the forecast cache key so that two requests for the same
+    /// location but different metrics don't collide.
+    fn cache_key(&self) -> String {
+        [
+            self.temp.then_some("temp"),
+            self.rain.then_some("rain"),
+            self.uv.then_some("uv"),
+            self.aqi.then_some("aqi"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(",")
+    }
+}
+
 /// Parse the Query from the request uri.
 fn get_query(req: &Request<IncomingBody>) -> Result<Query> {
     let uri = req.uri();
@@ -123,7 +203,7 @@ fn get_query(req: &Request<IncomingBody>) -> Result<Query> {
 /// massage the geolocation API response down to these fields because we dont
 /// care about a bunch of its contents. The Serialize allows us to return this
 /// value in our server response json.
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 struct Location {
     name: String,
     qualified_name: String,
@@ -135,6 +215,13 @@ struct Location {
 /// Fetch the locations corresponding to the query from the open-meteo
 /// geocoding API.
 async fn fetch_locations(query: &Query) -> Result<Vec<Location>> {
+    // Repeated lookups of the same city within the TTL window reuse the
+    // cached result instead of re-hitting the geocoding api.
+    let cache_key = cache::location_key(&query.city, query.count);
+    if let Some(cached) = cache::get_locations(&cache_key) {
+        return Ok(cached);
+    }
+
     // Utility struct describes the fields we use in the geocoding api's query
     // string
     #[derive(Serialize)]
@@ -161,13 +248,16 @@ async fn fetch_locations(query: &Query) -> Result<Vec<Location>> {
             serde_qs::to_string(&geo_query).context("serialize query string")?
         ))
         .build()?;
-    // Request is a GET request with no body. User agent is polite to provide.
-    let request = Request::get(uri)
-        .header("User-Agent", USER_AGENT)
-        .body(wstd::io::empty())?;
 
-    // Make the request
-    let resp = Client::new().send(request).await?;
+    // Make the request, with retries, backoff, and a circuit breaker for the
+    // geocoding-api.open-meteo.com origin. Request is a GET request with no
+    // body; user agent is polite to provide.
+    let resp = policy::send_with_policy(|| {
+        Ok(Request::get(uri.clone())
+            .header("User-Agent", USER_AGENT)
+            .body(wstd::io::empty())?)
+    })
+    .await?;
     // Die with 503 if geocoding api fails for some reason
     if resp.status() != StatusCode::OK {
         anyhow::bail!("geocoding-api returned status {:?}", resp.status());
@@ -240,22 +330,90 @@ async fn fetch_locations(query: &Query) -> Result<Vec<Location>> {
         .collect::<Vec<_>>();
     // Sort by highest population first.
     results.sort_by(|a, b| b.population.partial_cmp(&a.population).unwrap());
+    cache::put_locations(cache_key, results.clone());
     Ok(results)
 }
 
-/// Weather struct contains the items in the weather report we care about: the
-/// temperature, how much rain, and the units for each. The Serialize allows
-/// us to return this value in our server response json.
-#[derive(Debug, Serialize)]
+/// Weather struct contains the items in the weather report we care about:
+/// temperature, rain, UV index, and air quality, plus the units for each
+/// where the measurement has one. Fields are omitted from the serialized
+/// response when the corresponding metric wasn't requested, so requesting
+/// the default `temp,rain` set looks exactly like it did before `metrics`
+/// existed.
+#[derive(Debug, Clone, Default, Serialize)]
 struct Weather {
-    temp: f64,
-    temp_unit: String,
-    rain: f64,
-    rain_unit: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temp: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temp_unit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rain: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rain_unit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uv_index: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    european_aqi: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pm2_5: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pm2_5_unit: Option<String>,
+}
+
+/// Fetch the requested weather metrics for a given location, issuing the
+/// forecast and air-quality requests concurrently.
+async fn fetch_weather(location: &Location, metrics: Metrics) -> Result<Weather> {
+    // Repeated lookups of the same (quantized) coordinates and metric set
+    // within the TTL window reuse the cached result instead of re-hitting
+    // the forecast/air-quality apis.
+    let cache_key = cache::weather_key(location.latitude, location.longitude, &metrics.cache_key());
+    if let Some(cached) = cache::get_weather(&cache_key) {
+        return Ok(cached);
+    }
+
+    use futures_concurrency::future::TryJoin;
+    let (forecast, air_quality) = (
+        fetch_forecast(location, metrics),
+        fetch_air_quality(location, metrics),
+    )
+        .try_join()
+        .await?;
+    let forecast = forecast.unwrap_or_default();
+    let air_quality = air_quality.unwrap_or_default();
+
+    let weather = Weather {
+        temp: forecast.temp,
+        temp_unit: forecast.temp_unit,
+        rain: forecast.rain,
+        rain_unit: forecast.rain_unit,
+        uv_index: forecast.uv_index,
+        european_aqi: air_quality.european_aqi,
+        pm2_5: air_quality.pm2_5,
+        pm2_5_unit: air_quality.pm2_5_unit,
+    };
+    cache::put_weather(cache_key, weather.clone());
+    Ok(weather)
+}
+
+/// The subset of a forecast response we care about: temperature, rain, and
+/// UV index, whichever of those were requested.
+#[derive(Default)]
+struct Forecast {
+    temp: Option<f64>,
+    temp_unit: Option<String>,
+    rain: Option<f64>,
+    rain_unit: Option<String>,
+    uv_index: Option<f64>,
 }
 
-/// Fetch the weather for a given location from the open-meto forecast API.
-async fn fetch_weather(location: &Location) -> Result<Weather> {
+/// Fetch temperature, rain, and/or UV index from the open-meteo forecast
+/// API, whichever of those `metrics` selects. Returns `None` without making
+/// a request if none of them were requested.
+async fn fetch_forecast(location: &Location, metrics: Metrics) -> Result<Option<Forecast>> {
+    if !(metrics.temp || metrics.rain || metrics.uv) {
+        return Ok(None);
+    }
+
     // Utility struct for the query string expected by the forecast API
     #[derive(Serialize)]
     struct ForecastQuery {
@@ -267,7 +425,7 @@ async fn fetch_weather(location: &Location) -> Result<Weather> {
     let query = ForecastQuery {
         latitude: location.latitude,
         longitude: location.longitude,
-        current: "temperature_2m,rain".to_string(),
+        current: metrics.forecast_current(),
     };
     // Construct the uri to the forecast api, serializing the query string
     // with serde_qs.
@@ -279,11 +437,14 @@ async fn fetch_weather(location: &Location) -> Result<Weather> {
             serde_qs::to_string(&query).context("serialize query string")?
         ))
         .build()?;
-    // Make the GET request, attaching user-agent, empty body.
-    let request = Request::get(uri)
-        .header("User-Agent", USER_AGENT)
-        .body(wstd::io::empty())?;
-    let resp = Client::new().send(request).await?;
+    // Make the GET request, attaching user-agent, empty body. Goes through
+    // the same retry/backoff/circuit-breaker policy as the geocoding call.
+    let resp = policy::send_with_policy(|| {
+        Ok(Request::get(uri.clone())
+            .header("User-Agent", USER_AGENT)
+            .body(wstd::io::empty())?)
+    })
+    .await?;
 
     // Bubble up error if forecast api failed
     if resp.status() != StatusCode::OK {
@@ -291,7 +452,8 @@ async fn fetch_weather(location: &Location) -> Result<Weather> {
     }
 
     // Utility structs for extracting fields from the forecast api's json
-    // response.
+    // response. Only the metrics we asked for come back, so every field is
+    // optional here.
     #[derive(Deserialize)]
     struct Contents {
         current_units: Units,
@@ -299,25 +461,97 @@ async fn fetch_weather(location: &Location) -> Result<Weather> {
     }
     #[derive(Deserialize)]
     struct Units {
-        temperature_2m: String,
-        rain: String,
+        temperature_2m: Option<String>,
+        rain: Option<String>,
     }
     #[derive(Deserialize)]
     struct Data {
-        temperature_2m: f64,
-        rain: f64,
+        temperature_2m: Option<f64>,
+        rain: Option<f64>,
+        uv_index: Option<f64>,
     }
 
     // Parse the contents of the json response
     let contents: Contents = resp.into_body().json().await?;
-    // Massage those structs into a single Weather
-    let weather = Weather {
+    Ok(Some(Forecast {
         temp: contents.current.temperature_2m,
         temp_unit: contents.current_units.temperature_2m,
         rain: contents.current.rain,
         rain_unit: contents.current_units.rain,
+        uv_index: contents.current.uv_index,
+    }))
+}
+
+/// The subset of an air-quality response we care about: European AQI and
+/// PM2.5.
+#[derive(Default)]
+struct AirQuality {
+    european_aqi: Option<f64>,
+    pm2_5: Option<f64>,
+    pm2_5_unit: Option<String>,
+}
+
+/// Fetch European AQI and PM2.5 from the open-meteo air-quality API, if
+/// `metrics` selects `aqi`. Returns `None` without making a request
+/// otherwise.
+async fn fetch_air_quality(location: &Location, metrics: Metrics) -> Result<Option<AirQuality>> {
+    if !metrics.aqi {
+        return Ok(None);
+    }
+
+    // Utility struct for the query string expected by the air-quality API
+    #[derive(Serialize)]
+    struct AirQualityQuery {
+        latitude: f64,
+        longitude: f64,
+        current: String,
+    }
+    let query = AirQualityQuery {
+        latitude: location.latitude,
+        longitude: location.longitude,
+        current: "european_aqi,pm2_5".to_string(),
     };
-    Ok(weather)
+    let uri = Uri::builder()
+        .scheme("http")
+        .authority("air-quality-api.open-meteo.com")
+        .path_and_query(format!(
+            "/v1/air-quality?{}",
+            serde_qs::to_string(&query).context("serialize query string")?
+        ))
+        .build()?;
+    // Same retry/backoff/circuit-breaker policy as every other outbound call.
+    let resp = policy::send_with_policy(|| {
+        Ok(Request::get(uri.clone())
+            .header("User-Agent", USER_AGENT)
+            .body(wstd::io::empty())?)
+    })
+    .await?;
+
+    if resp.status() != StatusCode::OK {
+        anyhow::bail!("air-quality api returned status {:?}", resp.status());
+    }
+
+    #[derive(Deserialize)]
+    struct Contents {
+        current_units: Units,
+        current: Data,
+    }
+    #[derive(Deserialize)]
+    struct Units {
+        pm2_5: Option<String>,
+    }
+    #[derive(Deserialize)]
+    struct Data {
+        european_aqi: Option<f64>,
+        pm2_5: Option<f64>,
+    }
+
+    let contents: Contents = resp.into_body().json().await?;
+    Ok(Some(AirQuality {
+        european_aqi: contents.current.european_aqi,
+        pm2_5: contents.current.pm2_5,
+        pm2_5_unit: contents.current_units.pm2_5,
+    }))
 }
 
 /// The wstd http server runs `handle` and then packages the success or error into
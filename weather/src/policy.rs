@@ -0,0 +1,158 @@
+//! Retry-with-backoff and per-origin circuit breaking for outbound requests.
+//!
+//! The weather demo fans out many requests to `geocoding-api.open-meteo.com`
+//! and `api.open-meteo.com` while serving a single inbound request, so a
+//! single unlucky 5xx or connection failure shouldn't abort the whole
+//! response. Every outbound call should go through [`send_with_policy`]
+//! instead of calling `Client::new().send` directly.
+
+use anyhow::{Context, Result, anyhow};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use wstd::http::{Client, Request, Response, StatusCode, body::IncomingBody};
+
+/// How many times to retry a failed request before giving up.
+const MAX_RETRIES: u32 = 3;
+/// Starting backoff between retries, doubled after each attempt.
+const BASE_BACKOFF: Duration = Duration::from_millis(100);
+/// Backoff never grows past this.
+const MAX_BACKOFF: Duration = Duration::from_secs(2);
+/// Consecutive failures against one authority before the breaker opens.
+const FAILURE_THRESHOLD: u32 = 3;
+/// How long an open breaker stays open before allowing a probe request.
+const COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Circuit breaker state for a single URI authority (host and optional port).
+#[derive(Debug, Clone, Copy)]
+enum Breaker {
+    Closed { consecutive_failures: u32 },
+    Open { retry_at: Instant },
+    HalfOpen,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Breaker::Closed {
+            consecutive_failures: 0,
+        }
+    }
+}
+
+fn breakers() -> &'static Mutex<HashMap<String, Breaker>> {
+    static BREAKERS: OnceLock<Mutex<HashMap<String, Breaker>>> = OnceLock::new();
+    BREAKERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Send a request, retrying on connection failures and 5xx responses with
+/// exponential backoff, and tripping a per-authority circuit breaker if
+/// failures keep piling up. `build_request` is called once up front to read
+/// the request's authority, then once per attempt (including the first) so
+/// it can hand back a fresh request each time.
+///
+/// 4xx responses are returned immediately: they're not retried and don't
+/// count against the breaker, since retrying a client error just wastes
+/// time.
+pub async fn send_with_policy(
+    build_request: impl Fn() -> Result<Request<wstd::io::Empty>>,
+) -> Result<Response<IncomingBody>> {
+    let authority = request_authority(&build_request()?)?;
+
+    let mut backoff = BASE_BACKOFF;
+    for attempt in 0..=MAX_RETRIES {
+        breaker_precheck(&authority)?;
+
+        let request = build_request()?;
+        match Client::new().send(request).await {
+            Ok(resp) if resp.status().is_server_error() => {
+                record_failure(&authority);
+                if attempt == MAX_RETRIES {
+                    return Ok(resp);
+                }
+            }
+            Ok(resp) => {
+                record_success(&authority);
+                return Ok(resp);
+            }
+            Err(e) => {
+                record_failure(&authority);
+                if attempt == MAX_RETRIES {
+                    return Err(e).context("request failed after retries");
+                }
+            }
+        }
+
+        wstd::task::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+    unreachable!("the attempt == MAX_RETRIES branches above always return")
+}
+
+/// Pull the authority (host, used as the breaker key) out of a request's URI.
+fn request_authority(req: &Request<wstd::io::Empty>) -> Result<String> {
+    req.uri()
+        .authority()
+        .map(|a| a.to_string())
+        .ok_or_else(|| anyhow!("request uri {} is missing an authority", req.uri()))
+}
+
+/// Check whether the breaker for `authority` currently rejects requests. If
+/// the cooldown has elapsed on an open breaker, transitions it to half-open
+/// and lets this one probe request through. While half-open, every other
+/// caller is rejected until the in-flight probe resolves (via
+/// `record_success`/`record_failure`), so only a single probe is ever
+/// in-flight at once.
+fn breaker_precheck(authority: &str) -> Result<()> {
+    let mut breakers = breakers().lock().unwrap();
+    let state = breakers.entry(authority.to_string()).or_default();
+    match *state {
+        Breaker::Open { retry_at } if Instant::now() < retry_at => Err(anyhow!(
+            "circuit breaker open for {authority}"
+        )
+        .context(StatusCode::SERVICE_UNAVAILABLE)),
+        Breaker::Open { .. } => {
+            *state = Breaker::HalfOpen;
+            Ok(())
+        }
+        Breaker::HalfOpen => Err(anyhow!(
+            "circuit breaker for {authority} already has a probe in flight"
+        )
+        .context(StatusCode::SERVICE_UNAVAILABLE)),
+        Breaker::Closed { .. } => Ok(()),
+    }
+}
+
+/// Record a connection failure or 5xx response against `authority`'s
+/// breaker. A failed half-open probe reopens the breaker immediately; a
+/// closed breaker opens once consecutive failures hit `FAILURE_THRESHOLD`.
+fn record_failure(authority: &str) {
+    let mut breakers = breakers().lock().unwrap();
+    let state = breakers.entry(authority.to_string()).or_default();
+    *state = match *state {
+        Breaker::HalfOpen => Breaker::Open {
+            retry_at: Instant::now() + COOLDOWN,
+        },
+        Breaker::Closed { consecutive_failures } => {
+            let consecutive_failures = consecutive_failures + 1;
+            if consecutive_failures >= FAILURE_THRESHOLD {
+                Breaker::Open {
+                    retry_at: Instant::now() + COOLDOWN,
+                }
+            } else {
+                Breaker::Closed { consecutive_failures }
+            }
+        }
+        open @ Breaker::Open { .. } => open,
+    };
+}
+
+/// Record a non-5xx response against `authority`'s breaker, closing it.
+fn record_success(authority: &str) {
+    let mut breakers = breakers().lock().unwrap();
+    breakers.insert(
+        authority.to_string(),
+        Breaker::Closed {
+            consecutive_failures: 0,
+        },
+    );
+}